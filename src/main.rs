@@ -16,37 +16,54 @@ enum RegexElement {
     CharGroup {
         is_positive: bool,
         options: Vec<char>,
+        ranges: Vec<(char, char)>,
     },
     StartAnchor,
     EndAnchor,
+    WordBoundary,
+    NonWordBoundary,
     Quantifier {
         min: usize,
         max: Option<usize>,
         content: Box<RegexElement>,
     },
+    /// A `(...)` capture group. `index` is its 1-based capture number
+    /// (assigned in the order the opening parens appear); `alternatives`
+    /// holds one or more `|`-separated branches.
+    Group {
+        index: usize,
+        alternatives: Vec<Vec<RegexElement>>,
+    },
+    /// A `\1`-`\9` backreference to a previously captured group.
+    Backreference(usize),
 }
 
 impl RegexElement {
-    fn read<T: Iterator<Item = char>>(chars: &mut Peekable<T>) -> Result<Option<Self>> {
+    fn read<T: Iterator<Item = char>>(
+        chars: &mut Peekable<T>,
+        group_counter: &mut usize,
+    ) -> Result<Option<Self>> {
         let result = match chars.next() {
             Some('.') => RegexElement::Wildcard,
             Some('\\') => match chars.next() {
                 Some('d') => RegexElement::Class(RegexClass::Digit),
                 Some('w') => RegexElement::Class(RegexClass::Alphanumeric),
+                Some('s') => RegexElement::Class(RegexClass::Whitespace),
+                Some('b') => RegexElement::WordBoundary,
+                Some('B') => RegexElement::NonWordBoundary,
+                Some(d) if d.is_ascii_digit() && d != '0' => {
+                    RegexElement::Backreference(d.to_digit(10).unwrap() as usize)
+                }
                 Some(c) => bail!("Unknown escape sequence: \\{c}"),
                 None => bail!("Expected character after '\\'"),
             },
-            // FIXME: should fail if we reach the end of the string without closing ']'
-            // FIXME: handle escape sequences inside char groups
             Some('[') => {
                 let is_positive = chars.next_if_eq(&'^').is_none();
-                RegexElement::CharGroup {
-                    is_positive,
-                    options: chars.take_while(|c| c != &']').collect(),
-                }
+                RegexElement::read_char_group(chars, is_positive)?
             }
             Some('^') => RegexElement::StartAnchor,
             Some('$') => RegexElement::EndAnchor,
+            Some('(') => RegexElement::read_group(chars, group_counter)?,
             Some(c) => RegexElement::Literal(c),
             None => return Ok(None),
         };
@@ -75,135 +92,632 @@ impl RegexElement {
                     content: Box::new(result),
                 }
             }
+            Some('{') => {
+                chars.next();
+                let (min, max) = Self::read_brace_bounds(chars)?;
+                Self::Quantifier {
+                    min,
+                    max,
+                    content: Box::new(result),
+                }
+            }
             Some(_) | None => result,
         };
         Ok(Some(result))
     }
-    fn matches<'a>(&self, full_str: &'a str, start_index: usize) -> Option<&'a str> {
-        let str = &full_str.get(start_index..).unwrap_or_default();
-        println!("Trying to match {self:?} in {:?}", str);
-        let matches: Option<&'a str> = match self {
-            RegexElement::StartAnchor => {
-                if start_index == 0 {
-                    Some(Default::default())
-                } else {
+
+    /// Parses `m}`, `m,}` or `m,n}` after a `{` has already been consumed,
+    /// returning the `(min, max)` a `Quantifier` expects.
+    fn read_brace_bounds<T: Iterator<Item = char>>(
+        chars: &mut Peekable<T>,
+    ) -> Result<(usize, Option<usize>)> {
+        let min = Self::read_number(chars)?;
+        let max = match chars.peek() {
+            Some(',') => {
+                chars.next();
+                if chars.peek() == Some(&'}') {
                     None
-                }
-            }
-            RegexElement::EndAnchor => {
-                if str.is_empty() {
-                    Some(Default::default())
                 } else {
-                    None
+                    Some(Self::read_number(chars)?)
                 }
             }
-            RegexElement::Wildcard => {
-                if !str.is_empty() {
-                    Some(&str[..1])
-                } else {
-                    None
-                }
+            _ => Some(min),
+        };
+        match chars.next() {
+            Some('}') => {}
+            _ => bail!("Malformed quantifier: expected closing '}}'"),
+        }
+        if let Some(max) = max {
+            if max < min {
+                bail!("Malformed quantifier: max ({max}) is less than min ({min})");
             }
-            RegexElement::Literal(c) => {
-                if str.starts_with(*c) {
-                    Some(&str[..1])
-                } else {
-                    None
-                }
+        }
+        Ok((min, max))
+    }
+
+    /// Reads a run of ASCII digits, failing if there isn't at least one.
+    fn read_number<T: Iterator<Item = char>>(chars: &mut Peekable<T>) -> Result<usize> {
+        let mut digits = String::new();
+        while let Some(&c) = chars.peek() {
+            if !c.is_ascii_digit() {
+                break;
             }
-            RegexElement::Class(RegexClass::Digit) => {
-                if str.chars().next().map_or(false, |c| c.is_ascii_digit()) {
-                    Some(&str[..1])
-                } else {
-                    None
+            digits.push(c);
+            chars.next();
+        }
+        if digits.is_empty() {
+            bail!("Malformed quantifier: expected a number");
+        }
+        digits
+            .parse()
+            .with_context(|| anyhow!("Malformed quantifier: invalid number '{digits}'"))
+    }
+
+    /// Parses the body of a `(...)` group, assuming the opening `(` has
+    /// already been consumed. Splits top-level `|`s into alternatives and
+    /// claims the next capture index before descending into them, so outer
+    /// groups are numbered before the groups nested inside their branches.
+    fn read_group<T: Iterator<Item = char>>(
+        chars: &mut Peekable<T>,
+        group_counter: &mut usize,
+    ) -> Result<Self> {
+        *group_counter += 1;
+        let index = *group_counter;
+        let mut alternatives = Vec::new();
+        let mut current = Vec::new();
+        loop {
+            match chars.peek() {
+                Some(')') => {
+                    chars.next();
+                    break;
                 }
-            }
-            RegexElement::Class(RegexClass::Alphanumeric) => {
-                if str
-                    .chars()
-                    .next()
-                    .map_or(false, |c| c.is_ascii_alphanumeric() || c == '_')
-                {
-                    Some(&str[..1])
-                } else {
-                    None
+                Some('|') => {
+                    chars.next();
+                    alternatives.push(std::mem::take(&mut current));
                 }
+                Some(_) => match RegexElement::read(chars, group_counter)? {
+                    Some(element) => current.push(element),
+                    None => bail!("Unterminated group: expected ')'"),
+                },
+                None => bail!("Unterminated group: expected ')'"),
             }
-            RegexElement::CharGroup {
-                is_positive,
-                options,
-            } => {
-                if str
-                    .chars()
-                    .next()
-                    .map_or(false, |c| options.contains(&c) == *is_positive)
-                {
-                    Some(&str[..1])
-                } else {
-                    None
-                }
-            }
-            RegexElement::Quantifier { min, max, content } => {
-                let mut end_index = 0;
-                let mut match_count: usize = 0;
-                while let Some(inner_match) = content.matches(str, end_index) {
-                    match_count += 1;
-                    end_index += inner_match.len();
-                    if let Some(max) = max {
-                        if *max == match_count {
-                            break;
-                        }
+        }
+        alternatives.push(current);
+        Ok(RegexElement::Group { index, alternatives })
+    }
+
+    /// Parses the body of a `[...]` character class, assuming the opening
+    /// `[` (and an optional `^`) has already been consumed. `\d`/`\w` expand
+    /// into `ranges` (plus `_` into `options` for `\w`), `\s` expands into
+    /// the whitespace characters in `options`, any other escape is a literal
+    /// character, and `a-z`-style ranges are recognized unless the `-` is
+    /// immediately followed by the closing `]`, in which case it's a literal
+    /// `-`.
+    fn read_char_group<T: Iterator<Item = char>>(chars: &mut Peekable<T>, is_positive: bool) -> Result<Self> {
+        let mut options = Vec::new();
+        let mut ranges = Vec::new();
+        loop {
+            let c = match chars.next() {
+                Some(']') => break,
+                Some('\\') => match chars.next() {
+                    Some('d') => {
+                        ranges.push(('0', '9'));
+                        continue;
                     }
+                    Some('w') => {
+                        ranges.extend([('a', 'z'), ('A', 'Z'), ('0', '9')]);
+                        options.push('_');
+                        continue;
+                    }
+                    Some('s') => {
+                        options.extend([' ', '\t', '\n', '\r', '\x0c', '\x0b']);
+                        continue;
+                    }
+                    Some(c) => c,
+                    None => bail!("Unterminated character class: expected ']'"),
+                },
+                Some(c) => c,
+                None => bail!("Unterminated character class: expected ']'"),
+            };
+            if chars.peek() == Some(&'-') {
+                chars.next();
+                match chars.next() {
+                    Some(']') => {
+                        options.push(c);
+                        options.push('-');
+                        break;
+                    }
+                    Some('\\') => match chars.next() {
+                        Some(end) => ranges.push((c, end)),
+                        None => bail!("Unterminated character class: expected ']'"),
+                    },
+                    Some(end) => ranges.push((c, end)),
+                    None => bail!("Unterminated character class: expected ']'"),
                 }
-                if match_count >= *min {
-                    Some(&str[..end_index])
-                } else {
-                    None
-                }
+                continue;
             }
-        };
-        // #[cfg(debug_assertions)]
-        // {
-        //     let str_end = iter.clone().collect::<String>();
-        //     let str_match = &str_start[..str_start.len() - str_end.len()];
-        //     println!("Matched {self:?} in {str_match:?}? {matches}. Remaining chars: {str_end:?}",);
-        // }
-        println!("Pattern {self:?} matched {matches:?} in {str:?}",);
-        matches
+            options.push(c);
+        }
+        Ok(RegexElement::CharGroup { is_positive, options, ranges })
     }
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 enum RegexClass {
     Digit,
     Alphanumeric,
+    Whitespace,
 }
 
+impl RegexClass {
+    fn contains(&self, c: char) -> bool {
+        match self {
+            RegexClass::Digit => c.is_ascii_digit(),
+            RegexClass::Alphanumeric => is_word_char(c),
+            RegexClass::Whitespace => c.is_ascii_whitespace(),
+        }
+    }
+}
+
+/// The `\w` definition shared by the `\w` class and the `\b`/`\B`
+/// word-boundary assertions.
+fn is_word_char(c: char) -> bool {
+    c.is_ascii_alphanumeric() || c == '_'
+}
+
+/// A `\b`/`\B` boundary holds at `pos` when exactly one of the character
+/// before it and the character at it is a word character.
+fn at_word_boundary(chars: &[char], pos: usize) -> bool {
+    let before = pos.checked_sub(1).and_then(|i| chars.get(i)).copied();
+    let after = chars.get(pos).copied();
+    before.is_some_and(is_word_char) != after.is_some_and(is_word_char)
+}
+
+/// A single instruction in the compiled NFA program. Quantifiers lower to
+/// `Split`/`Jump` loops so the whole pattern becomes a flat list that the
+/// Pike VM in `run` can simulate without backtracking.
 #[derive(Debug)]
-struct Regex(Vec<RegexElement>);
+enum Instr {
+    Char(char),
+    Class(RegexClass),
+    CharGroup {
+        is_positive: bool,
+        options: Vec<char>,
+        ranges: Vec<(char, char)>,
+    },
+    Any,
+    AssertStart,
+    AssertEnd,
+    WordBoundary,
+    NonWordBoundary,
+    Split(usize, usize),
+    Jump(usize),
+    Match,
+}
 
-impl Regex {
-    fn matches(&self, s: &str) -> bool {
-        'regex_loop: for start_index in 0..=s.len() {
-            let mut start_index = start_index;
-            println!("Trying to match {self:?} in {s:?} starting at {start_index}");
-            for element in &self.0 {
-                let matches = element.matches(s, start_index.min(s.len()));
-                println!(
-                    "Input {:?} matched {element:?}? {}",
-                    &s[start_index..],
-                    matches.is_some(),
-                );
-                if let Some(str_match) = matches {
-                    start_index += str_match.len();
-                } else {
-                    start_index += 1;
-                    continue 'regex_loop;
+/// Appends a self-contained program (its `Split`/`Jump` targets are relative
+/// to its own start) onto `dst`, rewriting those targets by `dst`'s current
+/// length.
+fn append(dst: &mut Vec<Instr>, src: Vec<Instr>) {
+    let offset = dst.len();
+    dst.extend(src.into_iter().map(|instr| match instr {
+        Instr::Split(a, b) => Instr::Split(a + offset, b + offset),
+        Instr::Jump(a) => Instr::Jump(a + offset),
+        other => other,
+    }));
+}
+
+fn compile(elements: &[RegexElement]) -> Vec<Instr> {
+    let mut prog = Vec::new();
+    for element in elements {
+        append(&mut prog, compile_element(element));
+    }
+    prog
+}
+
+fn compile_element(element: &RegexElement) -> Vec<Instr> {
+    match element {
+        RegexElement::Wildcard => vec![Instr::Any],
+        RegexElement::Literal(c) => vec![Instr::Char(*c)],
+        RegexElement::Class(class) => vec![Instr::Class(class.clone())],
+        RegexElement::CharGroup { is_positive, options, ranges } => vec![Instr::CharGroup {
+            is_positive: *is_positive,
+            options: options.clone(),
+            ranges: ranges.clone(),
+        }],
+        RegexElement::StartAnchor => vec![Instr::AssertStart],
+        RegexElement::EndAnchor => vec![Instr::AssertEnd],
+        RegexElement::WordBoundary => vec![Instr::WordBoundary],
+        RegexElement::NonWordBoundary => vec![Instr::NonWordBoundary],
+        RegexElement::Quantifier { min, max, content } => compile_quantifier(*min, *max, content),
+        RegexElement::Group { alternatives, .. } => compile_alternation(alternatives),
+        RegexElement::Backreference(_) => {
+            unreachable!("backreferences are only matched through the backtracking path")
+        }
+    }
+}
+
+/// Whether any element needs the backtracking matcher. Backreferences make
+/// the language non-regular, so the NFA literally cannot express them — but
+/// a plain `(...)` group with no backreference anywhere in the pattern is
+/// just alternation, which `compile_alternation` already lowers to the NFA
+/// just fine (nothing here consumes capture spans except backreferences
+/// themselves). So this descends into `Group` alternatives and `Quantifier`
+/// content looking specifically for a `Backreference`, rather than bailing
+/// out the moment it sees any `Group` at all.
+fn needs_backtracking(elements: &[RegexElement]) -> bool {
+    elements.iter().any(|element| match element {
+        RegexElement::Backreference(_) => true,
+        RegexElement::Quantifier { content, .. } => needs_backtracking(std::slice::from_ref(content)),
+        RegexElement::Group { alternatives, .. } => {
+            alternatives.iter().any(|alt| needs_backtracking(alt))
+        }
+        _ => false,
+    })
+}
+
+/// Lowers `a|b|c` into a chain of `Split`s, each choosing between its branch
+/// and the start of the next `Split`, with every branch jumping to one
+/// shared exit label once matched. Mirrors `compile_quantifier`'s optional
+/// chain, just with each arm being mandatory-if-chosen instead of skippable.
+fn compile_alternation(branches: &[Vec<RegexElement>]) -> Vec<Instr> {
+    let mut prog = Vec::new();
+    let mut jump_positions = Vec::new();
+    for (i, branch) in branches.iter().enumerate() {
+        if i + 1 == branches.len() {
+            append(&mut prog, compile(branch));
+        } else {
+            let split_idx = prog.len();
+            prog.push(Instr::Split(0, 0));
+            let body_start = prog.len();
+            append(&mut prog, compile(branch));
+            jump_positions.push(prog.len());
+            prog.push(Instr::Jump(0));
+            let next_branch_start = prog.len();
+            prog[split_idx] = Instr::Split(body_start, next_branch_start);
+        }
+    }
+    let after = prog.len();
+    for idx in jump_positions {
+        prog[idx] = Instr::Jump(after);
+    }
+    prog
+}
+
+/// Lowers a quantifier into `min` mandatory copies of `content` followed by
+/// either an unbounded `Split`/`Jump` loop (no `max`) or a chain of optional
+/// copies sharing one exit label (`max` set). `?`, `*` and `+` are just the
+/// `min`/`max` pairs `(0,Some(1))`, `(0,None)` and `(1,None)`.
+fn compile_quantifier(min: usize, max: Option<usize>, content: &RegexElement) -> Vec<Instr> {
+    let mut prog = Vec::new();
+    for _ in 0..min {
+        append(&mut prog, compile_element(content));
+    }
+    match max {
+        None => {
+            let split_idx = prog.len();
+            prog.push(Instr::Split(0, 0));
+            let body_start = prog.len();
+            append(&mut prog, compile_element(content));
+            prog.push(Instr::Jump(split_idx));
+            let after = prog.len();
+            prog[split_idx] = Instr::Split(body_start, after);
+        }
+        Some(max) => {
+            let extra = max.saturating_sub(min);
+            let mut split_positions = Vec::with_capacity(extra);
+            for _ in 0..extra {
+                let split_idx = prog.len();
+                prog.push(Instr::Split(0, 0));
+                split_positions.push(split_idx);
+                append(&mut prog, compile_element(content));
+            }
+            let after = prog.len();
+            for idx in split_positions {
+                prog[idx] = Instr::Split(idx + 1, after);
+            }
+        }
+    }
+    prog
+}
+
+/// Follows the epsilon transitions (`Split`, `Jump`, and the zero-width
+/// assertions) starting at `pc`, pushing every `Char`/`Class`/`Any`/`Match`
+/// instruction it reaches onto `list`. `seen` deduplicates by program
+/// counter so loops (from `*`/`+`) terminate.
+fn add_thread(list: &mut Vec<usize>, seen: &mut [bool], prog: &[Instr], pc: usize, pos: usize, chars: &[char]) {
+    if seen[pc] {
+        return;
+    }
+    seen[pc] = true;
+    match &prog[pc] {
+        Instr::Jump(x) => add_thread(list, seen, prog, *x, pos, chars),
+        Instr::Split(x, y) => {
+            add_thread(list, seen, prog, *x, pos, chars);
+            add_thread(list, seen, prog, *y, pos, chars);
+        }
+        Instr::AssertStart => {
+            if pos == 0 {
+                add_thread(list, seen, prog, pc + 1, pos, chars);
+            }
+        }
+        Instr::AssertEnd => {
+            if pos == chars.len() {
+                add_thread(list, seen, prog, pc + 1, pos, chars);
+            }
+        }
+        Instr::WordBoundary => {
+            if at_word_boundary(chars, pos) {
+                add_thread(list, seen, prog, pc + 1, pos, chars);
+            }
+        }
+        Instr::NonWordBoundary => {
+            if !at_word_boundary(chars, pos) {
+                add_thread(list, seen, prog, pc + 1, pos, chars);
+            }
+        }
+        Instr::Char(_) | Instr::Class(_) | Instr::CharGroup { .. } | Instr::Any | Instr::Match => {
+            list.push(pc);
+        }
+    }
+}
+
+/// Pattern-level flags set by one leading `(?...)` group holding `i`
+/// and/or `s` in any order (`(?i)`, `(?s)`, `(?is)`, `(?si)`), in the style
+/// of the old `regex` crate's `FLAG_NOCASE`/`FLAG_DOTNL`. Only the first
+/// group is read as flags; a second one such as the `(?s)` in `(?i)(?s)` is
+/// parsed as an ordinary group.
+#[derive(Debug, Default, Clone, Copy)]
+struct Flags {
+    case_insensitive: bool,
+    dot_all: bool,
+}
+
+impl Flags {
+    /// Parses a leading `(?...)` flag group off of `chars`, leaving the
+    /// iterator untouched if what follows isn't one (a group such as `(?x)`
+    /// is then parsed as an ordinary group whose `?` is a literal character).
+    fn read<T: Iterator<Item = char> + Clone>(chars: &mut Peekable<T>) -> Result<Self> {
+        let mut lookahead = chars.clone();
+        if lookahead.next() != Some('(') || lookahead.next() != Some('?') {
+            return Ok(Self::default());
+        }
+        let mut flags = Self::default();
+        loop {
+            match lookahead.next() {
+                Some('i') => flags.case_insensitive = true,
+                Some('s') => flags.dot_all = true,
+                Some(')') => break,
+                _ => return Ok(Self::default()),
+            }
+        }
+        *chars = lookahead;
+        Ok(flags)
+    }
+}
+
+fn char_eq(flags: &Flags, a: char, b: char) -> bool {
+    if flags.case_insensitive {
+        a.eq_ignore_ascii_case(&b)
+    } else {
+        a == b
+    }
+}
+
+fn wildcard_matches(flags: &Flags, c: char) -> bool {
+    flags.dot_all || c != '\n'
+}
+
+fn char_in_range(flags: &Flags, c: char, lo: char, hi: char) -> bool {
+    if flags.case_insensitive {
+        (lo..=hi).contains(&c.to_ascii_lowercase()) || (lo..=hi).contains(&c.to_ascii_uppercase())
+    } else {
+        (lo..=hi).contains(&c)
+    }
+}
+
+/// Whether `c` belongs to a `[...]` character class's `options`/`ranges`,
+/// independent of `is_positive` (callers compare the result against it).
+fn char_in_group(flags: &Flags, c: char, options: &[char], ranges: &[(char, char)]) -> bool {
+    options.iter().any(|&o| char_eq(flags, c, o)) || ranges.iter().any(|&(lo, hi)| char_in_range(flags, c, lo, hi))
+}
+
+/// Pike VM simulation: `current`/`next` hold the program counters of every
+/// thread alive at the current input position. A new thread is seeded at
+/// every position (not just position 0) so the search isn't anchored unless
+/// the pattern itself starts with `^`.
+fn run(prog: &[Instr], input: &str, flags: &Flags) -> bool {
+    let chars: Vec<char> = input.chars().collect();
+    let mut current = Vec::new();
+    let mut next = Vec::new();
+    let mut seen = vec![false; prog.len()];
+    add_thread(&mut current, &mut seen, prog, 0, 0, &chars);
+    for pos in 0..=chars.len() {
+        if current.iter().any(|&pc| matches!(prog[pc], Instr::Match)) {
+            return true;
+        }
+        let c = chars.get(pos).copied();
+        let mut seen_next = vec![false; prog.len()];
+        for &pc in &current {
+            let advances = match &prog[pc] {
+                Instr::Char(expected) => c.is_some_and(|c| char_eq(flags, c, *expected)),
+                Instr::Class(class) => c.is_some_and(|c| class.contains(c)),
+                Instr::CharGroup { is_positive, options, ranges } => {
+                    c.is_some_and(|c| char_in_group(flags, c, options, ranges) == *is_positive)
+                }
+                Instr::Any => c.is_some_and(|c| wildcard_matches(flags, c)),
+                Instr::Match => false,
+                Instr::AssertStart
+                | Instr::AssertEnd
+                | Instr::WordBoundary
+                | Instr::NonWordBoundary
+                | Instr::Split(..)
+                | Instr::Jump(_) => {
+                    unreachable!("epsilon instructions never reach the thread list")
                 }
+            };
+            if advances {
+                add_thread(&mut next, &mut seen_next, prog, pc + 1, pos + 1, &chars);
             }
+        }
+        if pos < chars.len() {
+            add_thread(&mut next, &mut seen_next, prog, 0, pos + 1, &chars);
+        }
+        current.clear();
+        std::mem::swap(&mut current, &mut next);
+    }
+    false
+}
+
+/// A capture's `(start, end)` char-index span within the input, recorded as
+/// soon as its group finishes matching. `saves[0]` is the whole match;
+/// `saves[n]` is capture group `n`.
+type Saves = Vec<Option<(usize, usize)>>;
+
+/// The "rest of the pattern" a backtracking element tries to satisfy once
+/// it has consumed its own input. Calling it is what lets a quantifier or
+/// group give back characters when a later element can't match.
+type Cont<'a> = dyn FnMut(usize, &mut Saves) -> bool + 'a;
+
+/// The read-only context threaded through every backtracking call: the
+/// input being matched against and the pattern-level flags.
+struct MatchContext<'a> {
+    input: &'a [char],
+    flags: &'a Flags,
+}
+
+/// Matches `elements` starting at `idx`, deferring to `rest` once all of
+/// them have matched. This is the classic continuation-passing backtracking
+/// matcher: every element tries the most greedy thing first and only backs
+/// off when `rest` reports failure, which is what makes backreferences and
+/// capture groups possible (neither fits the Thompson NFA above).
+fn match_elements(elements: &[RegexElement], idx: usize, ctx: &MatchContext, saves: &mut Saves, rest: &mut Cont) -> bool {
+    match elements.split_first() {
+        None => rest(idx, saves),
+        Some((first, remaining)) => {
+            let mut cont = |idx: usize, saves: &mut Saves| match_elements(remaining, idx, ctx, saves, rest);
+            match_element(first, idx, ctx, saves, &mut cont)
+        }
+    }
+}
+
+fn match_element(element: &RegexElement, idx: usize, ctx: &MatchContext, saves: &mut Saves, rest: &mut Cont) -> bool {
+    let input = ctx.input;
+    match element {
+        RegexElement::Wildcard => {
+            input.get(idx).is_some_and(|&c| wildcard_matches(ctx.flags, c)) && rest(idx + 1, saves)
+        }
+        RegexElement::Literal(c) => {
+            input.get(idx).is_some_and(|&input_c| char_eq(ctx.flags, input_c, *c)) && rest(idx + 1, saves)
+        }
+        RegexElement::Class(class) => input.get(idx).is_some_and(|c| class.contains(*c)) && rest(idx + 1, saves),
+        RegexElement::CharGroup { is_positive, options, ranges } => {
+            input
+                .get(idx)
+                .is_some_and(|&c| char_in_group(ctx.flags, c, options, ranges) == *is_positive)
+                && rest(idx + 1, saves)
+        }
+        RegexElement::StartAnchor => idx == 0 && rest(idx, saves),
+        RegexElement::EndAnchor => idx == input.len() && rest(idx, saves),
+        RegexElement::WordBoundary => at_word_boundary(input, idx) && rest(idx, saves),
+        RegexElement::NonWordBoundary => !at_word_boundary(input, idx) && rest(idx, saves),
+        RegexElement::Quantifier { min, max, content } => {
+            match_repeat(content, 0, (*min, *max), idx, ctx, saves, rest)
+        }
+        RegexElement::Group { index, alternatives } => {
+            let index = *index;
+            for alternative in alternatives {
+                let saved = saves.clone();
+                let mut cont = |end_idx: usize, saves: &mut Saves| {
+                    saves[index] = Some((idx, end_idx));
+                    rest(end_idx, saves)
+                };
+                if match_elements(alternative, idx, ctx, saves, &mut cont) {
+                    return true;
+                }
+                *saves = saved;
+            }
+            false
+        }
+        RegexElement::Backreference(n) => match saves.get(*n).copied().flatten() {
+            Some((start, end)) => {
+                let captured = &input[start..end];
+                let len = end - start;
+                idx + len <= input.len()
+                    && input[idx..idx + len]
+                        .iter()
+                        .zip(captured)
+                        .all(|(&a, &b)| char_eq(ctx.flags, a, b))
+                    && rest(idx + len, saves)
+            }
+            None => false,
+        },
+    }
+}
+
+/// Greedily tries to match one more `content` repetition before falling
+/// back to `rest`, so quantifiers prefer the longest match but still give
+/// characters back on backtracking. A repetition that consumed no input is
+/// not retried, since that would loop forever without making progress.
+fn match_repeat(
+    content: &RegexElement,
+    count: usize,
+    bounds: (usize, Option<usize>),
+    idx: usize,
+    ctx: &MatchContext,
+    saves: &mut Saves,
+    rest: &mut Cont,
+) -> bool {
+    let (min, max) = bounds;
+    if max.is_none_or(|max| count < max) {
+        let saved = saves.clone();
+        let mut cont = |new_idx: usize, saves: &mut Saves| {
+            new_idx != idx && match_repeat(content, count + 1, bounds, new_idx, ctx, saves, rest)
+        };
+        if match_element(content, idx, ctx, saves, &mut cont) {
             return true;
         }
-        false
+        *saves = saved;
+    }
+    count >= min && rest(idx, saves)
+}
+
+#[derive(Debug)]
+struct Regex {
+    elements: Vec<RegexElement>,
+    num_groups: usize,
+    flags: Flags,
+}
+
+impl Regex {
+    /// Returns `None` if the pattern doesn't match anywhere in `s`. Patterns
+    /// without backreferences run through the linear-time NFA above, which
+    /// tracks no positions: a match there returns `Some` of an empty vector,
+    /// with no spans for the whole match or for any group. Patterns with a
+    /// backreference fall back to backtracking, which alone can express
+    /// them, and return the capture spans: index 0 is the whole match, and
+    /// index `n` is capture group `n`.
+    fn matches(&self, s: &str) -> Option<Saves> {
+        if !needs_backtracking(&self.elements) {
+            let mut prog = compile(&self.elements);
+            prog.push(Instr::Match);
+            return run(&prog, s, &self.flags).then(Vec::new);
+        }
+        let chars: Vec<char> = s.chars().collect();
+        let ctx = MatchContext { input: &chars, flags: &self.flags };
+        for start in 0..=chars.len() {
+            let mut saves = vec![None; self.num_groups + 1];
+            let mut end = None;
+            let mut record_end = |idx: usize, _: &mut Saves| {
+                end = Some(idx);
+                true
+            };
+            if match_elements(&self.elements, start, &ctx, &mut saves, &mut record_end) {
+                saves[0] = Some((start, end.unwrap()));
+                return Some(saves);
+            }
+        }
+        None
     }
 }
 
@@ -211,35 +725,88 @@ impl FromStr for Regex {
     type Err = anyhow::Error;
     fn from_str(s: &str) -> Result<Self> {
         let mut chars = s.chars().peekable();
-        let elements = std::iter::from_fn(move || RegexElement::read(&mut chars).transpose())
+        let flags = Flags::read(&mut chars)?;
+        let mut group_counter = 0;
+        let elements = std::iter::from_fn(move || RegexElement::read(&mut chars, &mut group_counter).transpose())
             .collect::<Result<Vec<_>, _>>()
             .with_context(|| anyhow!("Failed to parse regex"))?;
         if elements.is_empty() {
             bail!("Empty regex");
         }
         println!("Parsed regex elements: {elements:?}");
-        Ok(Self(elements))
+        let num_groups = count_groups(&elements);
+        Ok(Self { elements, num_groups, flags })
     }
 }
 
-fn match_pattern(input_line: &str, pattern: &str) -> Result<bool> {
-    Ok(Regex::from_str(pattern)?.matches(input_line))
+fn count_groups(elements: &[RegexElement]) -> usize {
+    elements
+        .iter()
+        .map(|element| match element {
+            RegexElement::Group { index, alternatives } => alternatives
+                .iter()
+                .map(|alt| count_groups(alt))
+                .max()
+                .unwrap_or(0)
+                .max(*index),
+            RegexElement::Quantifier { content, .. } => count_groups(std::slice::from_ref(content)),
+            _ => 0,
+        })
+        .max()
+        .unwrap_or(0)
 }
-// Usage: echo <input_text> | your_program.sh -E <pattern>
 
-fn main() -> Result<()> {
-    if env::args().nth(1).unwrap() != "-E" {
-        println!("Expected first argument to be '-E'");
-        process::exit(1);
+/// Several patterns matched against the same input. Each pattern is run on
+/// its own; the input is not shared between them in one pass.
+#[derive(Debug)]
+struct RegexSet(Vec<Regex>);
+
+impl RegexSet {
+    /// Returns the indices (in construction order) of every pattern that
+    /// matched `s`.
+    fn matches(&self, s: &str) -> Vec<usize> {
+        self.0
+            .iter()
+            .enumerate()
+            .filter(|(_, regex)| regex.matches(s).is_some())
+            .map(|(i, _)| i)
+            .collect()
     }
-    let pattern = env::args().nth(2).unwrap();
+}
+
+/// Parses one or more repeated `-E <pattern>` pairs off the command-line
+/// arguments (not counting argv[0]).
+fn parse_patterns(mut args: impl Iterator<Item = String>) -> Result<Vec<String>> {
+    let mut patterns = Vec::new();
+    while let Some(flag) = args.next() {
+        if flag != "-E" {
+            bail!("Expected '-E' before each pattern, got {flag:?}");
+        }
+        patterns.push(args.next().context("Expected a pattern after '-E'")?);
+    }
+    if patterns.is_empty() {
+        bail!("Expected at least one '-E <pattern>' argument");
+    }
+    Ok(patterns)
+}
+// Usage: echo <input_text> | your_program.sh -E <pattern> [-E <pattern> ...]
+
+fn main() -> Result<()> {
+    let patterns = parse_patterns(env::args().skip(1))?;
+    let set = RegexSet(
+        patterns
+            .iter()
+            .map(|pattern| Regex::from_str(pattern))
+            .collect::<Result<Vec<_>>>()?,
+    );
     let mut input_line = String::new();
     io::stdin().read_line(&mut input_line).unwrap();
-    if match_pattern(&input_line, &pattern)? {
-        process::exit(0)
-    } else {
-        process::exit(1)
+    let matched = set.matches(&input_line);
+    if matched.is_empty() {
+        process::exit(1);
     }
+    println!("Matched patterns: {matched:?}");
+    process::exit(0);
 }
 #[cfg(test)]
 mod test {
@@ -263,8 +830,6 @@ mod test {
             ("a?", "a", true),
             ("a?", "aa", true),
             ("a?b", "aaa", false),
-            // FIXME: this should be true
-            // ("a?b", "aaab", true),
             ("a?b", "aaab", true),
             ("a?b", "b", true),
             ("ca+t", "caaats", true),
@@ -273,7 +838,7 @@ mod test {
         for (pattern, input, expected) in &cases {
             println!("\nTesting {pattern:?} against {input:?} with expected result = {expected}");
             assert_eq!(
-                Regex::from_str(pattern).unwrap().matches(input),
+                Regex::from_str(pattern).unwrap().matches(input).is_some(),
                 *expected,
                 "Expected {pattern:?} {}to match {input:?}",
                 if *expected { "" } else { "not " }
@@ -293,11 +858,215 @@ mod test {
         for (pattern, input, expected) in &cases {
             println!("\nTesting {pattern:?} against {input:?} with expected result = {expected}");
             assert_eq!(
-                Regex::from_str(pattern).unwrap().matches(input),
+                Regex::from_str(pattern).unwrap().matches(input).is_some(),
+                *expected,
+                "Expected {pattern:?} {}to match {input:?}",
+                if *expected { "" } else { "not " }
+            );
+        }
+    }
+
+    #[test]
+    fn test_groups_and_alternation() {
+        let cases = [
+            ("(cat|dog)s", "cats", true),
+            ("(cat|dog)s", "dogs", true),
+            ("(cat|dog)s", "cows", false),
+            ("(a|b|c)+", "abcabc", true),
+            ("(a|b|c)+", "d", false),
+            ("(foo)(bar)", "foobar", true),
+            ("(foo)(bar)", "foo", false),
+            ("a(b|c)*d", "abccbd", true),
+        ];
+        for (pattern, input, expected) in &cases {
+            println!("\nTesting {pattern:?} against {input:?} with expected result = {expected}");
+            assert_eq!(
+                Regex::from_str(pattern).unwrap().matches(input).is_some(),
+                *expected,
+                "Expected {pattern:?} {}to match {input:?}",
+                if *expected { "" } else { "not " }
+            );
+        }
+    }
+
+    /// Plain alternation with no backreference anywhere in the pattern must
+    /// stay on the linear-time NFA path: `needs_backtracking` is checked to be
+    /// false for it, and the pattern is checked not to match a long run of
+    /// `a`s with no `c`.
+    #[test]
+    fn test_alternation_stays_linear_time() {
+        let input = "a".repeat(30);
+        let regex = Regex::from_str("(a|b){20,}c").unwrap();
+        assert!(!needs_backtracking(&regex.elements));
+        assert!(regex.matches(&input).is_none());
+    }
+
+    #[test]
+    fn test_backreferences() {
+        let cases = [
+            (r"(\w+)\s\1", "cat cat", true),
+            (r"(\w+)\s\1", "cat dog", false),
+            (r"(\w+) \1 \1", "cat cat cat", true),
+            (r"(\d+)-\1", "42-42", true),
+            (r"(\d+)-\1", "42-43", false),
+            (r"(cat|dog)s? and \1", "cat and cat", true),
+            (r"(cat|dog)s? and \1", "cats and dog", false),
+        ];
+        for (pattern, input, expected) in &cases {
+            println!("\nTesting {pattern:?} against {input:?} with expected result = {expected}");
+            assert_eq!(
+                Regex::from_str(pattern).unwrap().matches(input).is_some(),
+                *expected,
+                "Expected {pattern:?} {}to match {input:?}",
+                if *expected { "" } else { "not " }
+            );
+        }
+    }
+
+    #[test]
+    fn test_whitespace_class() {
+        let cases = [
+            (r"\s", " ", true),
+            (r"\s", "\t", true),
+            (r"\s", "a", false),
+            (r"a\sb", "a b", true),
+            (r"a\sb", "ab", false),
+            (r"[\s]", " ", true),
+            (r"[^\s]", " ", false),
+            (r"[^\s]", "a", true),
+        ];
+        for (pattern, input, expected) in &cases {
+            println!("\nTesting {pattern:?} against {input:?} with expected result = {expected}");
+            assert_eq!(
+                Regex::from_str(pattern).unwrap().matches(input).is_some(),
+                *expected,
+                "Expected {pattern:?} {}to match {input:?}",
+                if *expected { "" } else { "not " }
+            );
+        }
+    }
+
+    #[test]
+    fn test_brace_quantifier() {
+        let cases = [
+            ("a{3}", "aa", false),
+            ("a{3}", "aaa", true),
+            ("a{2,4}", "a", false),
+            ("a{2,4}", "aa", true),
+            ("a{2,4}", "aaaa", true),
+            ("a{2,}", "a", false),
+            ("a{2,}", "aa", true),
+            ("a{2,}", "aaaaaa", true),
+            ("ca{2,3}t", "cat", false),
+            ("ca{2,3}t", "caat", true),
+        ];
+        for (pattern, input, expected) in &cases {
+            println!("\nTesting {pattern:?} against {input:?} with expected result = {expected}");
+            assert_eq!(
+                Regex::from_str(pattern).unwrap().matches(input).is_some(),
+                *expected,
+                "Expected {pattern:?} {}to match {input:?}",
+                if *expected { "" } else { "not " }
+            );
+        }
+    }
+
+    #[test]
+    fn test_brace_quantifier_errors() {
+        assert!(Regex::from_str("a{2,1}").is_err());
+        assert!(Regex::from_str("a{2").is_err());
+        assert!(Regex::from_str("a{}").is_err());
+    }
+
+    #[test]
+    fn test_flags() {
+        let cases = [
+            ("(?i)abc", "ABC", true),
+            ("(?i)abc", "AbC", true),
+            ("(?i)abc", "abd", false),
+            ("abc", "ABC", false),
+            ("(?s).", "\n", true),
+            (".", "\n", false),
+            ("(?is)a.c", "A\nC", true),
+            ("(?i)(\\w+) \\1", "Cat cat", true),
+        ];
+        for (pattern, input, expected) in &cases {
+            println!("\nTesting {pattern:?} against {input:?} with expected result = {expected}");
+            assert_eq!(
+                Regex::from_str(pattern).unwrap().matches(input).is_some(),
+                *expected,
+                "Expected {pattern:?} {}to match {input:?}",
+                if *expected { "" } else { "not " }
+            );
+        }
+    }
+
+    #[test]
+    fn test_word_boundary() {
+        let cases = [
+            (r"\bcat\b", "a cat sat", true),
+            (r"\bcat\b", "concatenate", false),
+            (r"\bcat", "catalog", true),
+            (r"cat\b", "concat", true),
+            (r"\Bcat", "concat", true),
+            (r"\Bcat", "a cat", false),
+        ];
+        for (pattern, input, expected) in &cases {
+            println!("\nTesting {pattern:?} against {input:?} with expected result = {expected}");
+            assert_eq!(
+                Regex::from_str(pattern).unwrap().matches(input).is_some(),
                 *expected,
                 "Expected {pattern:?} {}to match {input:?}",
                 if *expected { "" } else { "not " }
             );
         }
     }
+
+    #[test]
+    fn test_regex_set() {
+        let set = RegexSet(
+            ["^cat", r"\d+", "(q|z)+"]
+                .iter()
+                .map(|p| Regex::from_str(p).unwrap())
+                .collect(),
+        );
+        assert_eq!(set.matches("cat123"), vec![0, 1]);
+        assert_eq!(set.matches("qzqz"), vec![2]);
+        assert_eq!(set.matches("nope"), Vec::<usize>::new());
+    }
+
+    #[test]
+    fn test_char_group_ranges() {
+        let cases = [
+            ("[abc]", "b", true),
+            ("[^abc]", "b", false),
+            ("[^abc]", "d", true),
+            (r"[\d]", "5", true),
+            (r"[\d]", "a", false),
+            (r"[\w]", "_", true),
+            (r"[\\]", "\\", true),
+            ("[a-z]", "m", true),
+            ("[a-z]", "M", false),
+            ("[0-9]", "7", true),
+            ("[^a-z]", "5", true),
+            ("[a-]", "-", true),
+            (r"[\d\w\\-]", "-", true),
+            (r"[\d\w\\-]", "!", false),
+        ];
+        for (pattern, input, expected) in &cases {
+            println!("\nTesting {pattern:?} against {input:?} with expected result = {expected}");
+            assert_eq!(
+                Regex::from_str(pattern).unwrap().matches(input).is_some(),
+                *expected,
+                "Expected {pattern:?} {}to match {input:?}",
+                if *expected { "" } else { "not " }
+            );
+        }
+    }
+
+    #[test]
+    fn test_char_group_unterminated_errors() {
+        assert!(Regex::from_str("[abc").is_err());
+        assert!(Regex::from_str("[a-").is_err());
+    }
 }